@@ -1,73 +1,204 @@
-use bit_vec::BitVec;
 use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+
+mod parse;
+
+/// Describes the shape of a sudoku-like board.
+///
+/// `order` is both the number of candidates per cell and the side length
+/// of the grid (a standard board has `order == 9`). `box_rows` x
+/// `box_cols` is the shape of a single box region and must multiply out
+/// to `order`, so non-square boxes (e.g. 2x3 boxes on a 6x6 board) are
+/// expressible too.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BoardSpec {
+	pub(crate) order: usize,
+	box_rows: usize,
+	box_cols: usize,
+}
+
+impl BoardSpec {
+	pub(crate) fn new(order:usize, box_rows:usize, box_cols:usize) -> Self {
+		assert_eq!(box_rows * box_cols, order, "box dimensions must multiply out to the board order");
+		BoardSpec { order, box_rows, box_cols }
+	}
+
+	pub(crate) fn cell_count(&self) -> usize {
+		self.order * self.order
+	}
+
+	/// The index sets for every row.
+	fn rows(&self) -> Vec<Vec<usize>> {
+		let r = self.order;
+		(0..self.cell_count()).step_by(r).map(|i| (i..i+r).collect()).collect()
+	}
+
+	/// The index sets for every column.
+	fn cols(&self) -> Vec<Vec<usize>> {
+		let r = self.order;
+		(0..r).map(|i| (i..self.cell_count()).step_by(r).collect()).collect()
+	}
+
+	/// The index sets for every box region.
+	fn boxes(&self) -> Vec<Vec<usize>> {
+		let r = self.order;
+		let (box_rows, box_cols) = (self.box_rows, self.box_cols);
+		// box_cols boxes stacked vertically, box_rows boxes across.
+		(0..box_cols)
+			.flat_map(|box_row| (0..box_rows).map(move |box_col| (box_row, box_col)))
+			.map(|(box_row, box_col)| {
+				let start = box_row * box_rows * r + box_col * box_cols;
+				(0..box_rows)
+					.flat_map(|dr| (0..box_cols).map(move |dc| start + dr*r + dc))
+					.collect()
+			})
+			.collect()
+	}
+
+	/// All three families of unit index-sets: rows, then columns, then boxes.
+	fn unit_sets(&self) -> Vec<Vec<usize>> {
+		let mut sets = self.rows();
+		sets.extend(self.cols());
+		sets.extend(self.boxes());
+		sets
+	}
+}
+
+/// A cell's remaining candidates, packed one bit per value (bit `v` means
+/// "value `v+1` is still possible"). `u32` covers every board size this
+/// solver supports, up to the 25-candidate case, in a single word with no
+/// allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cell(u32);
+
+impl Cell {
+	/// A cell that could still be any of the first `order` values.
+	fn full(order:usize) -> Self {
+		Cell((1u32 << order) - 1)
+	}
+
+	fn empty() -> Self {
+		Cell(0)
+	}
+
+	fn single(value:usize) -> Self {
+		Cell(1 << value)
+	}
+
+	fn get(&self, v:usize) -> bool {
+		self.0 & (1 << v) != 0
+	}
+
+	fn set(&mut self, v:usize, value:bool) {
+		if value {
+			self.0 |= 1 << v;
+		} else {
+			self.0 &= !(1 << v);
+		}
+	}
+
+	fn clear(&mut self) {
+		self.0 = 0;
+	}
+
+	fn count(&self) -> usize {
+		self.0.count_ones() as usize
+	}
+
+	/// Subtracts `other`'s candidates from `self`, returning whether
+	/// anything changed.
+	fn difference(&mut self, other:&Cell) -> bool {
+		let next = self.0 & !other.0;
+		let changed = next != self.0;
+		self.0 = next;
+		changed
+	}
+
+	/// Keeps only the candidates `self` shares with `other`, returning
+	/// whether anything changed.
+	fn and(&mut self, other:&Cell) -> bool {
+		let next = self.0 & other.0;
+		let changed = next != self.0;
+		self.0 = next;
+		changed
+	}
+
+	fn or(&mut self, other:&Cell) {
+		self.0 |= other.0;
+	}
+}
 
 trait SudokuElem {
     fn is_solved(&self) -> bool;
-    fn solutions(&self) -> Vec<BitVec>;
+    fn solutions(&self) -> Vec<Cell>;
     fn is_invalid(&self) -> bool;
     fn print(&self) -> String;
 }
 
 trait Sudoku {
-    fn print(&self);
-    fn print_compact(&self);
-    fn cascade(&mut self,idx:&Vec<usize>) -> bool;
-    fn cascade_over_sets(&mut self, unique_sets:&Vec<Vec<usize>>);
-    fn is_invalid(&self) -> bool; 
-    fn row_size(&self) -> usize;
+    fn print_compact(&self, spec:&BoardSpec);
+    fn cascade(&mut self,idx:&[usize], order:usize) -> bool;
+    fn hidden_singles(&mut self, idx:&[usize], order:usize) -> bool;
+    fn naked_tuples(&mut self, idx:&[usize], order:usize) -> bool;
+    fn hidden_tuples(&mut self, idx:&[usize], order:usize) -> bool;
+    fn cascade_over_sets(&mut self, unique_sets:&[Vec<usize>], order:usize);
+    fn is_invalid(&self) -> bool;
 }
 
-impl SudokuElem for BitVec {
+impl SudokuElem for Cell {
     /// Is this square solved?
     ///
     /// A solved sudoku square has only one possible answer
     fn is_solved(&self) -> bool {
-	self.iter().filter(|x| *x).count() == 1
+	self.count() == 1
     }
 
     /// Which solutions are left?
-    /// 
-    /// Since we are storing each possible answer as a bool
-    /// we just return a new bitvec for each true in our bitvec
-    fn solutions(&self) -> Vec<BitVec> {
-        self.iter().enumerate()
-            .filter(|(_,r)| *r)
-            .map(|(i,_)| {
-		let mut a = BitVec::from_elem(9,false); 
-		a.set(i,true);
-		a
-		})
-            .collect()
+    ///
+    /// Isolates each set bit in turn, lowest first, via `x & x.wrapping_neg()`.
+    fn solutions(&self) -> Vec<Cell> {
+        let mut remaining = self.0;
+        let mut out = Vec::new();
+        while remaining != 0 {
+            let lowest = remaining & remaining.wrapping_neg();
+            out.push(Cell(lowest));
+            remaining ^= lowest;
+        }
+        out
     }
 
     /// Is this square still valid?
     ///
     /// A sudoku square is invalid if there are no possible answers
     fn is_invalid(&self) -> bool {
-        self.none()
+        self.0 == 0
     }
 
     /// Print the possible values of this square
     fn print(&self) -> String {
-        let index:String = self.iter().enumerate()
-            .filter(|(_,r)| *r)
-            .map(|(i,_)| format!("{}",i+1))
-            .collect();
-        return index;
+        let mut remaining = self.0;
+        let mut index = String::new();
+        while remaining != 0 {
+            index.push_str(&(remaining.trailing_zeros() + 1).to_string());
+            remaining &= remaining - 1; // clear the lowest set bit
+        }
+        index
     }
 }
 
-impl Sudoku for Vec<BitVec> {
-	fn cascade(&mut self, idx:&Vec<usize>) -> bool {
+impl Sudoku for Vec<Cell> {
+	fn cascade(&mut self, idx:&[usize], order:usize) -> bool {
 		let mut changed_ever = false;
-		loop { 
+		loop {
 			let mut changed = false;
 			let solved_mask = idx.iter()
 				.map(|i| &self[*i])
-				.filter_map(|e| if e.is_solved() {Some(e)} else {None} )
-				.fold(BitVec::from_elem(9,false), |mut solved,i| {solved.or(i); solved} );
+				.filter(|e| e.is_solved())
+				.fold(Cell::empty(), |mut solved,i| {solved.or(i); solved} );
 
-			let mut set:HashSet<String> = HashSet::with_capacity(9);
+			let mut set:HashSet<String> = HashSet::with_capacity(order);
 			for i in idx {
 				let e = &mut self[*i];
 				//If its not solved, subtract the solved masked
@@ -86,21 +217,115 @@ impl Sudoku for Vec<BitVec> {
 		changed_ever
 	}
 
-	fn cascade_over_sets(&mut self, unique_sets:&Vec<Vec<usize>>) {
-		loop {
-			//cascade every set until nothing changes.
-			if !unique_sets.iter().map(|s| self.cascade(s)).fold(false, |a,b| a||b) {break}
+	/// Hidden single: if exactly one cell in the unit still allows
+	/// candidate `v`, that cell must hold `v`, even if it still looks
+	/// ambiguous on its own.
+	fn hidden_singles(&mut self, idx:&[usize], order:usize) -> bool {
+		let mut changed = false;
+		for v in 0..order {
+			let holders:Vec<usize> = idx.iter().cloned()
+				.filter(|&i| self[i].get(v))
+				.collect();
+			if let [i] = holders[..] {
+				if !self[i].is_solved() {
+					self[i] = Cell::single(v);
+					changed = true;
+				}
+			}
+		}
+		changed
+	}
+
+	/// Naked pair/triple: if `k` cells in the unit share an identical
+	/// candidate set of size `k`, none of those candidates can belong to
+	/// any other cell in the unit.
+	fn naked_tuples(&mut self, idx:&[usize], order:usize) -> bool {
+		let mut changed = false;
+		for k in 2..=3 {
+			// A tuple found at the previous k may have just solved a cell;
+			// make sure that's subtracted elsewhere before we look again.
+			if self.cascade(idx, order) {
+				changed = true;
+			}
+			let mut groups:Vec<(Cell,Vec<usize>)> = Vec::new();
+			for &i in idx {
+				if self[i].count() != k {
+					continue;
+				}
+				match groups.iter_mut().find(|(mask,_)| *mask == self[i]) {
+					Some(group) => group.1.push(i),
+					None => groups.push((self[i], vec![i])),
+				}
+			}
+			for (mask, cells) in groups.iter().filter(|(_,cells)| cells.len() == k) {
+				for &i in idx {
+					if !cells.contains(&i) && self[i].difference(mask) {
+						changed = true;
+					}
+				}
+			}
+		}
+		changed
+	}
+
+	/// Hidden pair/triple: if `k` candidate values collectively appear in
+	/// exactly `k` cells of the unit, those cells can hold nothing else.
+	fn hidden_tuples(&mut self, idx:&[usize], order:usize) -> bool {
+		let mut changed = false;
+		for k in 2..=3 {
+			// A tuple found at the previous k may have just solved a cell;
+			// make sure that's subtracted elsewhere before we look again.
+			if self.cascade(idx, order) {
+				changed = true;
+			}
+			// Only values that still have a plausible number of homes are
+			// worth combining; a value with zero occurrences isn't part of
+			// any real hidden tuple.
+			let sparse_values:Vec<usize> = (0..order)
+				.filter(|&v| {
+					let count = idx.iter()
+						.filter(|&&i| !self[i].is_solved() && self[i].get(v))
+						.count();
+					count >= 1 && count <= k
+				})
+				.collect();
+
+			for values in k_combinations(&sparse_values, k) {
+				let cells:Vec<usize> = idx.iter().cloned()
+					.filter(|&i| !self[i].is_solved() && values.iter().any(|&v| self[i].get(v)))
+					.collect();
+				if cells.len() != k {
+					continue;
+				}
+				let mut mask = Cell::empty();
+				for &v in &values {
+					mask.set(v,true);
+				}
+				for &i in &cells {
+					if self[i].and(&mask) {
+						changed = true;
+					}
+				}
+			}
 		}
+		changed
 	}
 
-	fn print(&self) {
-		let r = self.row_size();
-		for i in 0..self.len() {
-			println!("({},{}) {}", i/r,i%r, self[i].print());
+	fn cascade_over_sets(&mut self, unique_sets:&[Vec<usize>], order:usize) {
+		loop {
+			// cascade every set until nothing changes. Every set must run each
+			// pass regardless of earlier results, so this folds with `|`
+			// instead of calling `.any()`, which would short-circuit and skip
+			// cascading the remaining sets as soon as one of them changed.
+			let changed = unique_sets.iter()
+				.map(|s| self.cascade(s, order) | self.hidden_singles(s, order) | self.naked_tuples(s, order) | self.hidden_tuples(s, order))
+				.fold(false, |a,b| a | b);
+			if !changed {break}
 		}
 	}
-	fn print_compact(&self) {
-		let r = self.row_size();
+
+	fn print_compact(&self, spec:&BoardSpec) {
+		let r = spec.order;
 		for i in 0..r {
 			for j in 0..r {
 				print!("{}",match &self[r*i+j] {
@@ -109,83 +334,199 @@ impl Sudoku for Vec<BitVec> {
 				  _ => String::from("?"),
 				})
 			}
-			println!("")
+			println!()
 		}
 	}
 
 	fn is_invalid(&self) -> bool {
 		self.iter().any(|e| e.is_invalid())
 	}
-
-	fn row_size(&self) -> usize {
-		(self.len() as f64).sqrt() as usize
-	}
 }
 
 fn main() {
-	let sudoku:Vec<BitVec> = {
-		let sudoku = vec![
-		0, 0, 9, 4, 7, 0, 0, 0, 0,
-		8, 0, 6, 2, 0, 0, 7, 0, 0,
-		0, 0, 0, 0, 0, 1, 0, 0, 0,
-		9, 0, 3, 0, 0, 0, 0, 4, 0,
-		7, 1, 0, 0, 0, 0, 0, 5, 6,
-		0, 2, 0, 0, 0, 0, 8, 0, 3,
-		0, 0, 0, 6, 0, 0, 0, 0, 0,
-		0, 0, 7, 0, 0, 4, 9, 0, 8,
-		0, 0, 0, 0, 3, 7, 4, 0, 0
-		];
-		sudoku.iter().map(|x| build_elem(x)).collect()
+	let input = read_input();
+
+	let (spec, sudoku) = match parse::parse_puzzle(&input) {
+		Ok(parsed) => parsed,
+		Err(e) => {
+			eprintln!("error: {}", e);
+			std::process::exit(1);
+		}
 	};
 
-	let size = sudoku.len();
-	let row_size = sudoku.row_size();
-	let mut unique_sets:Vec<Vec<usize>> = Vec::new();
-	unique_sets.extend((0..size).step_by(row_size).map(|i| (i..i+row_size).collect()));
-	unique_sets.extend((0..row_size).map(|i| (i..size).step_by(row_size).collect()));
-	unique_sets.extend(
-			vec![0,3,6,27,30,33,54,57,60].iter()
-			.map(|i| vec![0+i,1+i,2+i,9+i,10+i,11+i,18+i,19+i,20+i])
-			);
+	let unique_sets = spec.unit_sets();
+
+	// Pull at most 2 solutions so we can tell "unique" from "ambiguous"
+	// without enumerating the whole search tree.
+	let solutions = find_solutions(sudoku, &unique_sets, spec.order, 2);
+	match solutions.as_slice() {
+		[] => println!("no solutions"),
+		[sol] => sol.print_compact(&spec),
+		[sol, ..] => {
+			println!("puzzle is ambiguous, showing one solution:");
+			sol.print_compact(&spec);
+		},
+	}
+}
+
+/// Reads the puzzle from the path given as the first CLI argument, or from
+/// stdin if no path was given.
+fn read_input() -> String {
+	match env::args().nth(1) {
+		Some(path) => fs::read_to_string(&path).unwrap_or_else(|e| {
+			eprintln!("error reading {}: {}", path, e);
+			std::process::exit(1);
+		}),
+		None => {
+			let mut buf = String::new();
+			io::stdin().read_to_string(&mut buf).expect("failed reading stdin");
+			buf
+		}
+	}
+}
+
+/// Lazily walks the cascade+backtracking search tree, yielding one
+/// completed grid per `next()` call instead of materializing every
+/// solution up front.
+struct SolutionIter<'a> {
+	unique_sets: &'a Vec<Vec<usize>>,
+	order: usize,
+	stack: Vec<(Vec<Cell>, usize)>,
+}
+
+impl<'a> Iterator for SolutionIter<'a> {
+	type Item = Vec<Cell>;
 
+	fn next(&mut self) -> Option<Vec<Cell>> {
+		while let Some((mut sudoku, idx)) = self.stack.pop() {
+			sudoku.cascade_over_sets(self.unique_sets, self.order);
+			if sudoku.is_invalid() {
+				continue;
+			}
 
-	match try_solutions(sudoku, 0, &unique_sets) {
-		Some(sol) => sol.print_compact(),
-			None => println!("no solutions"),
+			match (idx..sudoku.len()).find(|n| !sudoku[*n].is_solved()) {
+				Some(next_idx) => {
+					for solution in sudoku[next_idx].solutions() {
+						let mut branch = sudoku.clone();
+						branch[next_idx] = solution;
+						self.stack.push((branch, next_idx));
+					}
+				},
+				None => return Some(sudoku),
+			}
+		}
+		None
 	}
 }
 
-fn try_solutions(mut sudoku:Vec<BitVec>, idx:usize, unique_sets:&Vec<Vec<usize>>) -> Option<Vec<BitVec>> {
-	sudoku.cascade_over_sets(&unique_sets);
+/// Enumerates every solution to `sudoku`, searching lazily so callers can
+/// pull solutions one at a time without materializing them all.
+fn solve_all<'a>(sudoku:Vec<Cell>, unique_sets:&'a Vec<Vec<usize>>, order:usize) -> SolutionIter<'a> {
+	SolutionIter { unique_sets, order, stack: vec![(sudoku, 0)] }
+}
+
+/// Counts solutions to `sudoku`, stopping as soon as `limit` are found.
+/// Passing `2` is a cheap way to test whether a puzzle is well-posed
+/// (exactly one solution) without enumerating the rest.
+#[allow(dead_code)]
+fn count_solutions(sudoku:Vec<Cell>, unique_sets:&Vec<Vec<usize>>, order:usize, limit:usize) -> usize {
+	solve_all(sudoku, unique_sets, order).take(limit).count()
+}
+
+/// Collects up to `limit` solutions to `sudoku`.
+///
+/// With the `parallel` feature off this just drains `solve_all` lazily.
+/// With it on, branches near the root of the search tree are explored on
+/// the rayon thread pool instead, since that's where the widest, most
+/// independent fan-out lives.
+#[cfg(not(feature = "parallel"))]
+fn find_solutions(sudoku:Vec<Cell>, unique_sets:&Vec<Vec<usize>>, order:usize, limit:usize) -> Vec<Vec<Cell>> {
+	solve_all(sudoku, unique_sets, order).take(limit).collect()
+}
+
+/// Branches shallower than this depth are fanned out over the thread
+/// pool; deeper branches are cheap enough that sequential recursion beats
+/// the overhead of spawning more tasks.
+#[cfg(feature = "parallel")]
+const PARALLEL_DEPTH: usize = 2;
+
+#[cfg(feature = "parallel")]
+fn find_solutions(sudoku:Vec<Cell>, unique_sets:&Vec<Vec<usize>>, order:usize, limit:usize) -> Vec<Vec<Cell>> {
+	use std::sync::Mutex;
+	let found = Mutex::new(Vec::new());
+	search_parallel(sudoku, unique_sets, order, limit, 0, &found);
+	found.into_inner().unwrap()
+}
+
+/// Recursive cascade-then-branch search used by the `parallel` build.
+///
+/// Shares the same cascade-to-fixpoint-then-branch-on-the-first-unsolved-cell
+/// shape as `SolutionIter`, but recurses instead of pushing onto an
+/// explicit stack, since that's what lets `depth < PARALLEL_DEPTH`
+/// branches hand off to `rayon::iter::ParallelIterator` instead of a plain loop.
+#[cfg(feature = "parallel")]
+fn search_parallel(sudoku:Vec<Cell>, unique_sets:&Vec<Vec<usize>>, order:usize, limit:usize, depth:usize, found:&std::sync::Mutex<Vec<Vec<Cell>>>) {
+	use rayon::prelude::*;
+
+	if found.lock().unwrap().len() >= limit {
+		return;
+	}
+
+	let mut sudoku = sudoku;
+	sudoku.cascade_over_sets(unique_sets, order);
 	if sudoku.is_invalid() {
-		return None;
+		return;
 	}
 
-	match {
-		{idx..sudoku.len()}.filter(|n| match sudoku.get(*n) { 
-				Some(x) => !x.is_solved(),
-				None => false,
-				}).next()
-	} {
+	match (0..sudoku.len()).find(|n| !sudoku[*n].is_solved()) {
+		None => {
+			let mut found = found.lock().unwrap();
+			if found.len() < limit {
+				found.push(sudoku);
+			}
+		}
 		Some(next_idx) => {
-			sudoku[next_idx].solutions().iter()
-				.filter_map(|solution| {
-						let mut test = sudoku.to_vec();
-						test[next_idx] = solution.clone();
-						try_solutions(test, next_idx, unique_sets)
-						}).next()
-		},
-		None =>	Some(sudoku),
+			let branches = sudoku[next_idx].solutions();
+			if depth < PARALLEL_DEPTH {
+				branches.into_par_iter().for_each(|solution| {
+					let mut branch = sudoku.clone();
+					branch[next_idx] = solution;
+					search_parallel(branch, unique_sets, order, limit, depth + 1, found);
+				});
+			} else {
+				for solution in branches {
+					let mut branch = sudoku.clone();
+					branch[next_idx] = solution;
+					search_parallel(branch, unique_sets, order, limit, depth + 1, found);
+				}
+			}
+		}
+	}
+}
+
+/// All size-`k` combinations of `items`, used to hunt for hidden pairs/triples.
+fn k_combinations(items:&[usize], k:usize) -> Vec<Vec<usize>> {
+	fn helper(items:&[usize], start:usize, k:usize, current:&mut Vec<usize>, out:&mut Vec<Vec<usize>>) {
+		if current.len() == k {
+			out.push(current.clone());
+			return;
+		}
+		for i in start..items.len() {
+			current.push(items[i]);
+			helper(items, i+1, k, current, out);
+			current.pop();
+		}
 	}
+	let mut out = Vec::new();
+	helper(items, 0, k, &mut Vec::new(), &mut out);
+	out
 }
 
-fn build_elem(num:&usize) -> BitVec {
-	let mut bv = BitVec::from_elem(9,false);
+pub(crate) fn build_elem(num:&usize, order:usize) -> Cell {
 	if *num == 0 {
-		bv.negate(); // 0 means it can be any number 1-9.
+		Cell::full(order) // 0 means it can be any number 1 to `order`.
 	}
 	else {
-		bv.set(*num-1,true); 
+		Cell::single(*num-1)
 	}
-	return bv;
 }
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use crate::{build_elem, BoardSpec, Cell};
+
+/// Errors that can occur while parsing a puzzle from text.
+#[derive(Debug)]
+pub enum ParseError {
+	/// The single-line format's length isn't a perfect square, so no
+	/// square board order can be derived from it.
+	NotAPerfectSquare(usize),
+	/// A character in the single-line format wasn't a digit or `.`.
+	InvalidDigit(char),
+	/// The CSV header declared a non-square board; this solver only
+	/// supports order x order grids.
+	NotASquareBoard { rows: usize, cols: usize },
+	/// The header's (or derived) box dimensions don't multiply out to
+	/// the board's order.
+	InvalidBoxDimensions { order: usize, box_rows: usize, box_cols: usize },
+	/// The derived or declared order was zero, which has no cells and no
+	/// valid row/column/box index sets.
+	ZeroOrder,
+	/// A row, column, or value fell outside the board's range.
+	OutOfRange { row: usize, col: usize, value: usize },
+	/// A line didn't parse as the expected shape at all.
+	Malformed(String),
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ParseError::NotAPerfectSquare(length) =>
+				write!(f, "{} cells isn't a perfect square; a board's order can't be inferred from it", length),
+			ParseError::InvalidDigit(c) =>
+				write!(f, "invalid character '{}', expected a digit or '.'", c),
+			ParseError::NotASquareBoard { rows, cols } =>
+				write!(f, "expected an order x order board, header declared {}x{}", rows, cols),
+			ParseError::InvalidBoxDimensions { order, box_rows, box_cols } =>
+				write!(f, "box dimensions {}x{} don't multiply out to order {}", box_rows, box_cols, order),
+			ParseError::ZeroOrder =>
+				write!(f, "a board must have a positive order"),
+			ParseError::OutOfRange { row, col, value } =>
+				write!(f, "value {} at ({},{}) is out of range", value, row, col),
+			ParseError::Malformed(line) =>
+				write!(f, "could not parse line: {}", line),
+		}
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a puzzle from either the single-line or CSV format, choosing
+/// based on whether the first non-empty line contains a comma. The board's
+/// shape is derived from the input itself rather than assumed up front, so
+/// a 16x16 or non-square-box puzzle works the same way a 9x9 one does.
+pub fn parse_puzzle(input: &str) -> Result<(BoardSpec, Vec<Cell>), ParseError> {
+	let first_line = input.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("");
+	if first_line.contains(',') {
+		from_csv(input)
+	} else {
+		from_line(first_line)
+	}
+}
+
+/// Parses the common single-line format, where `.` or `0` means an empty
+/// cell and a digit is a given clue. The board's order is the integer
+/// square root of the line's length (81 chars -> order 9, 256 -> order
+/// 16, ...), with square boxes of side `sqrt(order)`; puzzles with
+/// non-square boxes need the CSV format instead, since a bare line of
+/// digits carries no way to say otherwise.
+pub fn from_line(line: &str) -> Result<(BoardSpec, Vec<Cell>), ParseError> {
+	let chars: Vec<char> = line.chars().collect();
+	if chars.is_empty() {
+		return Err(ParseError::ZeroOrder);
+	}
+	let order = isqrt(chars.len());
+	if order * order != chars.len() {
+		return Err(ParseError::NotAPerfectSquare(chars.len()));
+	}
+	let spec = square_spec(order)?;
+
+	let cells = chars.into_iter().enumerate().map(|(i, c)| {
+		let value = match c {
+			'.' => 0,
+			d if d.is_ascii_digit() => d.to_digit(10).unwrap() as usize,
+			other => return Err(ParseError::InvalidDigit(other)),
+		};
+		if value > order {
+			return Err(ParseError::OutOfRange { row: i / order, col: i % order, value });
+		}
+		Ok(build_elem(&value, order))
+	}).collect::<Result<Vec<Cell>, ParseError>>()?;
+
+	Ok((spec, cells))
+}
+
+/// Parses the coordinate CSV format: a header line followed by
+/// `row,col,value` triples for each given clue. Cells not mentioned are
+/// left empty. The header is `rows,cols` for a square board whose boxes
+/// default to `sqrt(order)` x `sqrt(order)`, or `rows,cols,box_rows,box_cols`
+/// to say exactly what shape the boxes are (e.g. `6,6,2,3`).
+pub fn from_csv(input: &str) -> Result<(BoardSpec, Vec<Cell>), ParseError> {
+	let mut lines = input.lines().map(str::trim).filter(|l| !l.is_empty());
+
+	let header = lines.next().ok_or_else(|| ParseError::Malformed(String::from("")))?;
+	let spec = parse_header(header)?;
+	let order = spec.order;
+
+	let mut cells: Vec<Cell> = (0..spec.cell_count()).map(|_| build_elem(&0, order)).collect();
+
+	for line in lines {
+		let mut fields = line.split(',').map(str::trim);
+		let row = parse_field(fields.next(), line)?;
+		let col = parse_field(fields.next(), line)?;
+		let value = parse_field(fields.next(), line)?;
+
+		if row >= order || col >= order || value == 0 || value > order {
+			return Err(ParseError::OutOfRange { row, col, value });
+		}
+		cells[row * order + col] = build_elem(&value, order);
+	}
+
+	Ok((spec, cells))
+}
+
+/// Parses a CSV header of either `rows,cols` or `rows,cols,box_rows,box_cols`
+/// into a `BoardSpec`, defaulting the box shape to `sqrt(order)` x
+/// `sqrt(order)` when it isn't given explicitly.
+fn parse_header(header: &str) -> Result<BoardSpec, ParseError> {
+	let fields: Vec<&str> = header.split(',').map(str::trim).collect();
+	let rows = parse_field(fields.first().copied(), header)?;
+	let cols = parse_field(fields.get(1).copied(), header)?;
+	if rows != cols {
+		return Err(ParseError::NotASquareBoard { rows, cols });
+	}
+	let order = rows;
+
+	match (fields.get(2), fields.get(3)) {
+		(Some(_), Some(_)) => {
+			let box_rows = parse_field(fields.get(2).copied(), header)?;
+			let box_cols = parse_field(fields.get(3).copied(), header)?;
+			if order == 0 {
+				return Err(ParseError::ZeroOrder);
+			}
+			if box_rows * box_cols != order {
+				return Err(ParseError::InvalidBoxDimensions { order, box_rows, box_cols });
+			}
+			Ok(BoardSpec::new(order, box_rows, box_cols))
+		}
+		_ => square_spec(order),
+	}
+}
+
+/// A square board of the given order with `sqrt(order)` x `sqrt(order)`
+/// boxes, the shape every classic sudoku (4x4, 9x9, 16x16, 25x25) uses.
+fn square_spec(order: usize) -> Result<BoardSpec, ParseError> {
+	if order == 0 {
+		return Err(ParseError::ZeroOrder);
+	}
+	let side = isqrt(order);
+	if side * side != order {
+		return Err(ParseError::InvalidBoxDimensions { order, box_rows: side, box_cols: side });
+	}
+	Ok(BoardSpec::new(order, side, side))
+}
+
+/// The integer square root of `n`, rounded to the nearest integer; callers
+/// confirm the result is exact by squaring it back.
+fn isqrt(n: usize) -> usize {
+	(n as f64).sqrt().round() as usize
+}
+
+fn parse_field(field: Option<&str>, line: &str) -> Result<usize, ParseError> {
+	field.and_then(|s| s.trim().parse().ok()).ok_or_else(|| ParseError::Malformed(line.to_string()))
+}